@@ -1,11 +1,17 @@
 use anyhow::Result;
 use chrono::{DateTime, Timelike, Utc};
 use clap::Parser;
+use inotify::{EventMask, Inotify, WatchMask};
+use ipnet::IpNet;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Seek};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(name = "iptables_report")]
@@ -22,163 +28,767 @@ struct Args {
     /// Show top N source IPs
     #[arg(short, long, default_value = "10")]
     top: usize,
+
+    /// Trusted CIDR network to exclude from analysis, e.g. 10.0.0.0/8 (repeatable)
+    #[arg(long)]
+    trustnets: Vec<String>,
+
+    /// File containing one trusted CIDR network per line
+    #[arg(long)]
+    trustnets_file: Option<PathBuf>,
+
+    /// Run fail2ban-style offender detection and print block rules
+    #[arg(long)]
+    block: bool,
+
+    /// Sliding time window (seconds) used to detect offenders
+    #[arg(long, default_value = "600")]
+    findtime: i64,
+
+    /// Denials within `findtime` needed to mark a source IP an offender
+    #[arg(long, default_value = "5")]
+    maxretry: usize,
+
+    /// Ban duration (seconds) applied to detected offenders
+    #[arg(long, default_value = "3600")]
+    bantime: i64,
+
+    /// Block rule format: iptables, nftables, or json
+    #[arg(long, default_value = "iptables")]
+    block_format: String,
+
+    /// TOML config defining named per-source log formats (see `SourcesConfig`)
+    #[arg(long)]
+    sources_config: Option<PathBuf>,
+
+    /// Keep running, tailing the log file for new entries instead of exiting
+    #[arg(long)]
+    follow: bool,
+
+    /// Seconds between periodic report re-emits in --follow mode
+    #[arg(long, default_value = "60")]
+    interval: i64,
+
+    /// POST parsed entries to a remote aggregation endpoint
+    #[arg(long)]
+    push_url: Option<String>,
+
+    /// Bearer token used to authenticate with --push-url / --fetch-url
+    #[arg(long)]
+    push_token: Option<String>,
+
+    /// Pull entries reported by other hosts from a remote aggregation endpoint
+    #[arg(long)]
+    fetch_url: Option<String>,
+
+    /// How far back to pull entries from --fetch-url, e.g. "3 hours"
+    #[arg(long, default_value = "3 hours")]
+    fetch_interval: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct IptablesEntry {
     timestamp: DateTime<Utc>,
-    source_ip: String,
-    dest_ip: String,
+    source_ip: IpAddr,
+    dest_ip: IpAddr,
     dest_port: Option<u16>,
     protocol: String,
     interface: Option<String>,
     chain: String,
     action: String,
+    /// Address family: `4` or `6`.
+    family: u8,
+    /// Source label: `"kernel"`, or a `--sources-config` set's `src`.
+    source: String,
+}
+
+/// A named log format loaded from `--sources-config`.
+#[derive(Debug, Clone, Deserialize)]
+struct SourceSet {
+    filename: String,
+    src: String,
+    regex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourcesConfig {
+    sets: HashMap<String, SourceSet>,
+}
+
+struct CompiledSourceSet {
+    filename: String,
+    src: String,
+    regex: Regex,
+}
+
+/// Loads and compiles `--sources-config`, ordered by set name for deterministic matching.
+fn load_sources_config(path: &PathBuf) -> Result<Vec<CompiledSourceSet>> {
+    let contents = std::fs::read_to_string(path)?;
+    let config: SourcesConfig = toml::from_str(&contents)?;
+
+    let mut names: Vec<String> = config.sets.keys().cloned().collect();
+    names.sort();
+
+    let mut sets = config.sets;
+    names
+        .into_iter()
+        .map(|name| {
+            let set = sets.remove(&name).expect("name came from this map's keys");
+            Ok(CompiledSourceSet {
+                filename: set.filename,
+                src: set.src,
+                regex: Regex::new(&set.regex)?,
+            })
+        })
+        .collect()
+}
+
+/// Every source set whose `filename` matches the log file being read.
+fn matching_source_sets<'a>(sources: &'a [CompiledSourceSet], path: &Path) -> Vec<&'a CompiledSourceSet> {
+    let filename = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+    sources.iter().filter(|set| filename.contains(set.filename.as_str())).collect()
+}
+
+/// Tries each matching source set in order, falling back to the kernel parser.
+fn parse_line(line: &str, matching: &[&CompiledSourceSet]) -> Option<IptablesEntry> {
+    for set in matching {
+        if let Some(entry) = parse_regex_line(line, set) {
+            return Some(entry);
+        }
+    }
+    parse_kernel_line(line)
 }
 
 #[derive(Debug, Serialize)]
 struct AnalysisReport {
     total_denials: usize,
+    /// Source IPs ranked by denial count - the actual attackers.
+    top_source_ips: Vec<(String, usize)>,
+    /// Destination IPs ranked by denial count - the hosts being targeted.
     top_dest_ips: Vec<(String, usize)>,
     protocol_distribution: HashMap<String, usize>,
     port_distribution: HashMap<u16, usize>,
     chain_distribution: HashMap<String, usize>,
     hourly_distribution: HashMap<u32, usize>,
+    family_distribution: HashMap<u8, usize>,
+    source_distribution: HashMap<String, usize>,
+    trusted_excluded: usize,
     entries: Vec<IptablesEntry>,
 }
 
+/// A source IP banned for exceeding `maxretry` denials within `findtime`.
+#[derive(Debug, Clone, Serialize)]
+struct Offender {
+    source_ip: String,
+    family: u8,
+    ban_start: DateTime<Utc>,
+    ban_until: DateTime<Utc>,
+    hit_count: usize,
+    ports: Vec<u16>,
+    protocols: Vec<String>,
+}
+
+/// An entry tagged with the host that denied it, for the remote endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RemoteEntry {
+    hostname: String,
+    #[serde(flatten)]
+    entry: IptablesEntry,
+}
+
+/// POSTs `entries` to `url` in batches, tagged with this host's hostname.
+fn push_entries(url: &str, token: Option<&str>, entries: &[IptablesEntry]) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    let batch: Vec<RemoteEntry> = entries
+        .iter()
+        .cloned()
+        .map(|entry| RemoteEntry {
+            hostname: hostname.clone(),
+            entry,
+        })
+        .collect();
+
+    const BATCH_SIZE: usize = 500;
+    let client = reqwest::blocking::Client::new();
+    for chunk in batch.chunks(BATCH_SIZE) {
+        let mut request = client.post(url).json(chunk);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send()?.error_for_status()?;
+    }
+
+    Ok(())
+}
+
+/// Fetches entries reported by other hosts in the last `interval`.
+fn fetch_remote_entries(url: &str, token: Option<&str>, interval: &str) -> Result<Vec<IptablesEntry>> {
+    let client = reqwest::blocking::Client::new();
+    let mut request = client.get(url).query(&[("interval", interval)]);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    let remote: Vec<RemoteEntry> = request.send()?.error_for_status()?.json()?;
+    Ok(remote.into_iter().map(|r| r.entry).collect())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
-    
-    let entries = parse_log_file(&args.log_file)?;
-    let report = analyze_entries(entries);
-    
+
+    let sources = match &args.sources_config {
+        Some(path) => load_sources_config(path)?,
+        None => Vec::new(),
+    };
+
+    if args.follow {
+        return run_follow(&args, &sources);
+    }
+
+    let entries = parse_log_file(&args.log_file, &sources)?;
+    let trustnets = build_trustnets(&args)?;
+    let (mut entries, trusted_excluded) = filter_trusted(entries, &trustnets);
+
+    if let Some(push_url) = &args.push_url {
+        push_entries(push_url, args.push_token.as_deref(), &entries)?;
+    }
+
+    if let Some(fetch_url) = &args.fetch_url {
+        let remote = fetch_remote_entries(fetch_url, args.push_token.as_deref(), &args.fetch_interval)?;
+        entries.extend(remote);
+    }
+
+    let report = analyze_entries(entries, trusted_excluded);
+
     match args.format.as_str() {
         "json" => println!("{}", serde_json::to_string_pretty(&report)?),
         _ => print_text_report(&report, args.top),
     }
-    
+
+    if args.block {
+        let offenders = detect_offenders(&report.entries, args.findtime, args.maxretry, args.bantime);
+        println!("\n=== BLOCK RULES ===\n");
+        println!("{}", format_block_rules(&offenders, &args.block_format)?);
+    }
+
     Ok(())
 }
 
-fn parse_log_file(path: &PathBuf) -> Result<Vec<IptablesEntry>> {
+fn parse_log_file(path: &PathBuf, sources: &[CompiledSourceSet]) -> Result<Vec<IptablesEntry>> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
-    
+
+    let matching = matching_source_sets(sources, path);
+
     let mut entries = Vec::new();
-    
+
     for line in reader.lines() {
         let line = line?;
-        
-        // Skip lines that don't contain kernel iptables entries
-        if !line.contains("kernel:") || !line.contains("DROP_IPV4") {
-            continue;
+
+        if let Some(entry) = parse_line(&line, &matching) {
+            entries.push(entry);
         }
-        
-        // Split line by spaces and parse key-value pairs
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 6 {
-            continue;
+    }
+
+    Ok(entries)
+}
+
+/// Parses the built-in `kernel:` + `DROP_IPV*` + `KEY=value` syslog shape.
+fn parse_kernel_line(line: &str) -> Option<IptablesEntry> {
+    // Skip lines that don't contain kernel iptables entries
+    if !line.contains("kernel:") {
+        return None;
+    }
+
+    // Accept any DROP_IPV* marker (DROP_IPV4, DROP_IPV6, ...) and record
+    // which address family it denotes.
+    let family = line.find("DROP_IPV").and_then(|pos| {
+        line[pos + "DROP_IPV".len()..]
+            .chars()
+            .next()
+            .and_then(|c| c.to_digit(10))
+    })? as u8;
+
+    // Split line by spaces and parse key-value pairs
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 6 {
+        return None;
+    }
+
+    // Extract timestamp (first part)
+    let timestamp_str = parts[0];
+    let timestamp = DateTime::parse_from_str(timestamp_str, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+    // Extract chain (part after "kernel:")
+    let chain = parts[3].trim_end_matches(':').to_string();
+
+    // Parse key-value pairs
+    let mut source_ip = String::new();
+    let mut dest_ip = String::new();
+    let mut protocol = String::new();
+    let mut interface = None;
+    let mut dest_port = None;
+
+    for part in &parts {
+        if part.contains('=') {
+            let mut kv = part.split('=');
+            if let Some(key) = kv.next() {
+                if let Some(value) = kv.next() {
+                    match key {
+                        "SRC" => source_ip = value.to_string(),
+                        "DST" => dest_ip = value.to_string(),
+                        "PROTO" => protocol = value.to_string(),
+                        "OUT" if !value.is_empty() => interface = Some(value.to_string()),
+                        "DPT" => dest_port = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
         }
-        
-        // Extract timestamp (first part)
-        let timestamp_str = parts[0];
-        let timestamp = DateTime::parse_from_str(timestamp_str, "%Y-%m-%dT%H:%M:%S%.f%z")
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now());
-        
-        // Extract chain (part after "kernel:")
-        let chain = parts[3].trim_end_matches(':').to_string();
-        
-        // Parse key-value pairs
-        let mut source_ip = String::new();
-        let mut dest_ip = String::new();
-        let mut protocol = String::new();
-        let mut interface = None;
-        let mut dest_port = None;
-        
-        for part in &parts {
-            if part.contains('=') {
-                let mut kv = part.split('=');
-                if let Some(key) = kv.next() {
-                    if let Some(value) = kv.next() {
-                        match key {
-                            "SRC" => source_ip = value.to_string(),
-                            "DST" => dest_ip = value.to_string(),
-                            "PROTO" => protocol = value.to_string(),
-                            "OUT" => if !value.is_empty() { interface = Some(value.to_string()); },
-                            "DPT" => dest_port = value.parse().ok(),
-                            _ => {}
+    }
+
+    // Only keep the entry if we have the required fields, and reject
+    // malformed addresses rather than storing them as raw strings.
+    if protocol.is_empty() {
+        return None;
+    }
+    let source_ip: IpAddr = source_ip.parse().ok()?;
+    let dest_ip: IpAddr = dest_ip.parse().ok()?;
+
+    Some(IptablesEntry {
+        timestamp,
+        source_ip,
+        dest_ip,
+        dest_port,
+        protocol,
+        interface,
+        chain,
+        action: "DENIED".to_string(),
+        family,
+        source: "kernel".to_string(),
+    })
+}
+
+/// Parses a line using a configured source set's named-capture regex.
+fn parse_regex_line(line: &str, set: &CompiledSourceSet) -> Option<IptablesEntry> {
+    let caps = set.regex.captures(line)?;
+
+    let source_ip: IpAddr = caps.name("source_ip")?.as_str().parse().ok()?;
+    let dest_ip: IpAddr = caps.name("dest_ip")?.as_str().parse().ok()?;
+    let family = if source_ip.is_ipv4() { 4 } else { 6 };
+
+    let protocol = caps
+        .name("protocol")
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default();
+    let dest_port = caps
+        .name("dest_port")
+        .and_then(|m| m.as_str().parse().ok());
+    let chain = caps
+        .name("chain")
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default();
+    let interface = caps.name("interface").map(|m| m.as_str().to_string());
+    let timestamp = caps
+        .name("timestamp")
+        .and_then(|m| DateTime::parse_from_str(m.as_str(), "%Y-%m-%dT%H:%M:%S%.f%z").ok())
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+
+    Some(IptablesEntry {
+        timestamp,
+        source_ip,
+        dest_ip,
+        dest_port,
+        protocol,
+        interface,
+        chain,
+        action: "DENIED".to_string(),
+        family,
+        source: set.src.clone(),
+    })
+}
+
+/// Tails `args.log_file`, re-emitting a rolling report on interval/SIGHUP.
+fn run_follow(args: &Args, sources: &[CompiledSourceSet]) -> Result<()> {
+    let trustnets = build_trustnets(args)?;
+
+    let initial = parse_log_file(&args.log_file, sources)?;
+    let (initial, mut trusted_excluded) = filter_trusted(initial, &trustnets);
+
+    if let Some(push_url) = &args.push_url {
+        if let Err(err) = push_entries(push_url, args.push_token.as_deref(), &initial) {
+            eprintln!("warning: failed to push entries to {push_url}: {err:#}");
+        }
+    }
+
+    let mut all_entries = initial;
+
+    if let Some(fetch_url) = &args.fetch_url {
+        let remote = fetch_remote_entries(fetch_url, args.push_token.as_deref(), &args.fetch_interval)?;
+        all_entries.extend(remote);
+    }
+
+    let mut offset = std::fs::metadata(&args.log_file)?.len();
+
+    let hup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hup))?;
+
+    let mut inotify = Inotify::init()?;
+    let watch_mask = WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::MOVE_SELF | WatchMask::DELETE_SELF;
+    let mut watch = inotify.watches().add(&args.log_file, watch_mask)?;
+
+    notify_ready();
+    let mut last_emit = Utc::now();
+    let mut buffer = [0u8; 4096];
+    // Poll for inotify events instead of blocking on them, so the
+    // --interval/SIGHUP re-emit checks below still run while the log is idle.
+    let poll_interval = std::time::Duration::from_millis(200);
+
+    loop {
+        let mut rotated = false;
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    for event in events {
+                        if event.mask.contains(EventMask::MOVE_SELF) || event.mask.contains(EventMask::DELETE_SELF) {
+                            rotated = true;
                         }
                     }
                 }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
             }
         }
-        
-        // Only add entry if we have the required fields
-        if !source_ip.is_empty() && !dest_ip.is_empty() && !protocol.is_empty() {
-            entries.push(IptablesEntry {
-                timestamp,
-                source_ip,
-                dest_ip,
-                dest_port,
-                protocol,
-                interface,
-                chain,
-                action: "DENIED".to_string(),
-            });
+
+        if rotated {
+            // Log rotated out from under us; re-open and re-watch from the start.
+            let _ = inotify.watches().remove(watch);
+            watch = inotify.watches().add(&args.log_file, watch_mask)?;
+            offset = 0;
+        } else if let Ok(metadata) = std::fs::metadata(&args.log_file) {
+            if metadata.len() < offset {
+                // Truncated in place (e.g. `> logfile`).
+                offset = 0;
+            }
         }
+
+        let (new_entries, new_offset) = read_new_lines(&args.log_file, offset, sources)?;
+        offset = new_offset;
+
+        let (kept, excluded) = filter_trusted(new_entries, &trustnets);
+        trusted_excluded += excluded;
+
+        if let Some(push_url) = &args.push_url {
+            // A transient failure of the remote collector (timeout, 5xx, DNS
+            // hiccup) shouldn't take down a long-lived --follow daemon; log
+            // it and keep tailing rather than propagating with `?`.
+            if let Err(err) = push_entries(push_url, args.push_token.as_deref(), &kept) {
+                eprintln!("warning: failed to push entries to {push_url}: {err:#}");
+            }
+        }
+
+        all_entries.extend(kept);
+
+        let due = Utc::now().signed_duration_since(last_emit).num_seconds() >= args.interval;
+        if hup.swap(false, Ordering::Relaxed) || due {
+            let report = analyze_entries(all_entries.clone(), trusted_excluded);
+            print_text_report(&report, args.top);
+
+            if args.block {
+                let offenders = detect_offenders(&report.entries, args.findtime, args.maxretry, args.bantime);
+                println!("\n=== BLOCK RULES ===\n");
+                println!("{}", format_block_rules(&offenders, &args.block_format)?);
+            }
+
+            notify_status(&report);
+            last_emit = Utc::now();
+        }
+
+        std::thread::sleep(poll_interval);
     }
-    
-    Ok(entries)
 }
 
-fn analyze_entries(entries: Vec<IptablesEntry>) -> AnalysisReport {
+/// Parses lines appended to `path` after byte offset `from`.
+fn read_new_lines(
+    path: &PathBuf,
+    from: u64,
+    sources: &[CompiledSourceSet],
+) -> Result<(Vec<IptablesEntry>, u64)> {
+    let mut file = File::open(path)?;
+    file.seek(std::io::SeekFrom::Start(from))?;
+
+    let matching = matching_source_sets(sources, path);
+    let reader = BufReader::new(&file);
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(entry) = parse_line(&line, &matching) {
+            entries.push(entry);
+        }
+    }
+
+    let new_offset = file.metadata()?.len();
+    Ok((entries, new_offset))
+}
+
+#[cfg(target_os = "linux")]
+fn notify_ready() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify_ready() {}
+
+#[cfg(target_os = "linux")]
+fn notify_status(report: &AnalysisReport) {
+    let status = format!(
+        "STATUS=denials={} sources={}",
+        report.total_denials,
+        report.source_distribution.len()
+    );
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Status(&status)]);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify_status(_report: &AnalysisReport) {}
+
+/// Collects trusted CIDR networks from `--trustnets` and `--trustnets-file`.
+fn build_trustnets(args: &Args) -> Result<Vec<IpNet>> {
+    let mut nets = Vec::new();
+
+    for cidr in &args.trustnets {
+        nets.push(cidr.parse::<IpNet>()?);
+    }
+
+    if let Some(path) = &args.trustnets_file {
+        let file = File::open(path)?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            nets.push(line.parse::<IpNet>()?);
+        }
+    }
+
+    Ok(nets)
+}
+
+/// Drops entries whose `source_ip` falls inside a trusted network.
+fn filter_trusted(entries: Vec<IptablesEntry>, trustnets: &[IpNet]) -> (Vec<IptablesEntry>, usize) {
+    if trustnets.is_empty() {
+        return (entries, 0);
+    }
+
+    let mut excluded = 0;
+    let kept = entries
+        .into_iter()
+        .filter(|entry| {
+            let trusted = trustnets.iter().any(|net| net.contains(&entry.source_ip));
+            if trusted {
+                excluded += 1;
+            }
+            !trusted
+        })
+        .collect();
+
+    (kept, excluded)
+}
+
+fn analyze_entries(entries: Vec<IptablesEntry>, trusted_excluded: usize) -> AnalysisReport {
     let total_denials = entries.len();
     
+    let mut source_ip_counts = HashMap::new();
     let mut dest_ip_counts = HashMap::new();
     let mut protocol_counts = HashMap::new();
     let mut port_counts = HashMap::new();
     let mut chain_counts = HashMap::new();
     let mut hourly_counts = HashMap::new();
-    
+    let mut family_counts = HashMap::new();
+    let mut source_counts = HashMap::new();
+
     for entry in &entries {
-        *dest_ip_counts.entry(entry.dest_ip.clone()).or_insert(0) += 1;
+        *source_ip_counts.entry(entry.source_ip.to_string()).or_insert(0) += 1;
+        *dest_ip_counts.entry(entry.dest_ip.to_string()).or_insert(0) += 1;
         *protocol_counts.entry(entry.protocol.clone()).or_insert(0) += 1;
         *chain_counts.entry(entry.chain.clone()).or_insert(0) += 1;
-        
+        *family_counts.entry(entry.family).or_insert(0) += 1;
+        *source_counts.entry(entry.source.clone()).or_insert(0) += 1;
+
         if let Some(port) = entry.dest_port {
             *port_counts.entry(port).or_insert(0) += 1;
         }
-        
+
         let hour = entry.timestamp.hour();
         *hourly_counts.entry(hour).or_insert(0) += 1;
     }
-    
+
+    let mut top_source_ips: Vec<_> = source_ip_counts.into_iter().collect();
+    top_source_ips.sort_by_key(|entry| std::cmp::Reverse(entry.1));
+
     let mut top_dest_ips: Vec<_> = dest_ip_counts.into_iter().collect();
-    top_dest_ips.sort_by(|a, b| b.1.cmp(&a.1));
-    
+    top_dest_ips.sort_by_key(|entry| std::cmp::Reverse(entry.1));
+
     AnalysisReport {
         total_denials,
+        top_source_ips,
         top_dest_ips,
         protocol_distribution: protocol_counts,
         port_distribution: port_counts,
         chain_distribution: chain_counts,
         hourly_distribution: hourly_counts,
+        family_distribution: family_counts,
+        source_distribution: source_counts,
+        trusted_excluded,
         entries,
     }
 }
 
+/// Flags source IPs with `maxretry` denials inside a `findtime` window.
+fn detect_offenders(
+    entries: &[IptablesEntry],
+    findtime: i64,
+    maxretry: usize,
+    bantime: i64,
+) -> Vec<Offender> {
+    let mut by_source: HashMap<IpAddr, Vec<&IptablesEntry>> = HashMap::new();
+    for entry in entries {
+        by_source.entry(entry.source_ip).or_default().push(entry);
+    }
+
+    let mut offenders = Vec::new();
+
+    for (source_ip, mut hits) in by_source {
+        hits.sort_by_key(|e| e.timestamp);
+
+        let window = chrono::Duration::seconds(findtime);
+        // (ban_start, ban_until, first hit index, last hit index) for each
+        // qualifying window, in ascending order of ban_start.
+        let mut bans: Vec<(DateTime<Utc>, DateTime<Utc>, usize, usize)> = Vec::new();
+        let mut start = 0usize;
+
+        for end in 0..hits.len() {
+            while hits[end].timestamp - hits[start].timestamp > window {
+                start += 1;
+            }
+            if end - start + 1 >= maxretry {
+                let ban_start = hits[end].timestamp;
+                bans.push((ban_start, ban_start + chrono::Duration::seconds(bantime), start, end));
+            }
+        }
+
+        if bans.is_empty() {
+            continue;
+        }
+
+        // Collapse only bans whose intervals actually overlap (or touch)
+        // into one; disjoint bursts become separate offender entries.
+        let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>, usize, usize)> = Vec::new();
+        for (ban_start, ban_until, first_idx, last_idx) in bans {
+            if let Some(last) = merged.last_mut() {
+                if ban_start <= last.1 {
+                    last.1 = last.1.max(ban_until);
+                    last.3 = last.3.max(last_idx);
+                    continue;
+                }
+            }
+            merged.push((ban_start, ban_until, first_idx, last_idx));
+        }
+
+        for (ban_start, ban_until, first_idx, last_idx) in merged {
+            let window_hits = &hits[first_idx..=last_idx];
+
+            let mut ports: Vec<u16> = window_hits.iter().filter_map(|e| e.dest_port).collect();
+            ports.sort_unstable();
+            ports.dedup();
+
+            let mut protocols: Vec<String> = window_hits.iter().map(|e| e.protocol.clone()).collect();
+            protocols.sort_unstable();
+            protocols.dedup();
+
+            offenders.push(Offender {
+                source_ip: source_ip.to_string(),
+                family: if source_ip.is_ipv6() { 6 } else { 4 },
+                ban_start,
+                ban_until,
+                hit_count: window_hits.len(),
+                ports,
+                protocols,
+            });
+        }
+    }
+
+    offenders.sort_by(|a, b| a.source_ip.cmp(&b.source_ip));
+    offenders
+}
+
+/// Renders one block rule per offender in the requested format.
+fn format_block_rules(offenders: &[Offender], format: &str) -> Result<String> {
+    match format {
+        "iptables" => Ok(offenders
+            .iter()
+            .map(|o| {
+                let bin = if o.family == 6 { "ip6tables" } else { "iptables" };
+                format!("{bin} -A INPUT -s {} -j DROP", o.source_ip)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        "nftables" => Ok(offenders
+            .iter()
+            .map(|o| {
+                let family = if o.family == 6 { "ip6" } else { "ip" };
+                format!("nft add rule inet filter input {family} saddr {} drop", o.source_ip)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        "json" => Ok(serde_json::to_string_pretty(offenders)?),
+        other => anyhow::bail!("unknown block format: {other}"),
+    }
+}
+
 fn print_text_report(report: &AnalysisReport, top_n: usize) {
     println!("=== IPTABLES DENIAL REPORT ===\n");
     println!("Total denials: {}\n", report.total_denials);
-    
-    println!("TOP {} DESTINATION IPs (Attackers):", top_n);
+
+    if report.trusted_excluded > 0 {
+        println!(
+            "Excluded by trust filtering: {}\n",
+            report.trusted_excluded
+        );
+    }
+
+    println!("ADDRESS FAMILY DISTRIBUTION:");
+    println!(
+        "  IPv4: {} denials",
+        report.family_distribution.get(&4).copied().unwrap_or(0)
+    );
+    println!(
+        "  IPv6: {} denials",
+        report.family_distribution.get(&6).copied().unwrap_or(0)
+    );
+    println!();
+
+    println!("TOP {} SOURCE IPs (Attackers):", top_n);
+    for (ip, count) in report.top_source_ips.iter().take(top_n) {
+        println!("  {}: {} denials", ip, count);
+    }
+    println!();
+
+    println!("TOP {} DESTINATION IPs (Targeted Hosts):", top_n);
     for (ip, count) in report.top_dest_ips.iter().take(top_n) {
         println!("  {}: {} denials", ip, count);
     }
     println!();
-    
+
     println!("PROTOCOL DISTRIBUTION:");
     for (protocol, count) in &report.protocol_distribution {
         println!("  {}: {}", protocol, count);
@@ -188,7 +798,7 @@ fn print_text_report(report: &AnalysisReport, top_n: usize) {
     if !report.port_distribution.is_empty() {
         println!("TOP DESTINATION PORTS:");
         let mut ports: Vec<_> = report.port_distribution.iter().collect();
-        ports.sort_by(|a, b| b.1.cmp(&a.1));
+        ports.sort_by_key(|entry| std::cmp::Reverse(*entry.1));
         for (port, count) in ports.iter().take(10) {
             println!("  {}: {} denials", port, count);
         }
@@ -200,11 +810,115 @@ fn print_text_report(report: &AnalysisReport, top_n: usize) {
         println!("  {}: {}", chain, count);
     }
     println!();
-    
+
+    if report.source_distribution.len() > 1 {
+        println!("SOURCE DISTRIBUTION:");
+        for (source, count) in &report.source_distribution {
+            println!("  {}: {}", source, count);
+        }
+        println!();
+    }
+
     println!("HOURLY DISTRIBUTION:");
     for hour in 0..24 {
         if let Some(count) = report.hourly_distribution.get(&hour) {
             println!("  {:02}:00: {} denials", hour, count);
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ip: &str, secs_offset: i64) -> IptablesEntry {
+        IptablesEntry {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + secs_offset, 0).unwrap(),
+            source_ip: ip.parse().unwrap(),
+            dest_ip: "10.0.0.1".parse().unwrap(),
+            dest_port: Some(22),
+            protocol: "TCP".to_string(),
+            interface: None,
+            chain: "INPUT".to_string(),
+            action: "DROP".to_string(),
+            family: if ip.contains(':') { 6 } else { 4 },
+            source: "kernel".to_string(),
+        }
+    }
+
+    #[test]
+    fn detect_offenders_merges_only_overlapping_bans() {
+        // Five hits within the findtime window (a burst), then a second
+        // burst far outside bantime of the first should stay separate.
+        let entries: Vec<IptablesEntry> = vec![
+            entry("1.2.3.4", 0),
+            entry("1.2.3.4", 10),
+            entry("1.2.3.4", 20),
+            entry("1.2.3.4", 30),
+            entry("1.2.3.4", 40),
+            entry("1.2.3.4", 100_000),
+            entry("1.2.3.4", 100_010),
+            entry("1.2.3.4", 100_020),
+            entry("1.2.3.4", 100_030),
+            entry("1.2.3.4", 100_040),
+        ];
+
+        let offenders = detect_offenders(&entries, 600, 5, 3600);
+
+        assert_eq!(offenders.len(), 2, "disjoint bursts must not be merged into one ban");
+        assert_eq!(offenders[0].hit_count, 5);
+        assert_eq!(offenders[1].hit_count, 5);
+        assert!(offenders[0].ban_until < offenders[1].ban_start);
+    }
+
+    #[test]
+    fn detect_offenders_merges_touching_bans() {
+        // Two qualifying windows whose ban intervals overlap should
+        // collapse into a single offender spanning both.
+        let entries: Vec<IptablesEntry> = vec![
+            entry("1.2.3.4", 0),
+            entry("1.2.3.4", 10),
+            entry("1.2.3.4", 20),
+            entry("1.2.3.4", 30),
+            entry("1.2.3.4", 40),
+            entry("1.2.3.4", 100),
+        ];
+
+        let offenders = detect_offenders(&entries, 600, 5, 3600);
+
+        assert_eq!(offenders.len(), 1);
+        assert_eq!(offenders[0].hit_count, 6);
+    }
+
+    #[test]
+    fn format_block_rules_uses_ipv6_tooling_for_ipv6_offenders() {
+        let offenders = vec![
+            Offender {
+                source_ip: "2001:db8::1".to_string(),
+                family: 6,
+                ban_start: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                ban_until: DateTime::from_timestamp(1_700_003_600, 0).unwrap(),
+                hit_count: 5,
+                ports: vec![22],
+                protocols: vec!["TCP".to_string()],
+            },
+            Offender {
+                source_ip: "203.0.113.5".to_string(),
+                family: 4,
+                ban_start: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                ban_until: DateTime::from_timestamp(1_700_003_600, 0).unwrap(),
+                hit_count: 5,
+                ports: vec![22],
+                protocols: vec!["TCP".to_string()],
+            },
+        ];
+
+        let iptables = format_block_rules(&offenders, "iptables").unwrap();
+        assert!(iptables.contains("ip6tables -A INPUT -s 2001:db8::1 -j DROP"));
+        assert!(iptables.contains("iptables -A INPUT -s 203.0.113.5 -j DROP"));
+
+        let nftables = format_block_rules(&offenders, "nftables").unwrap();
+        assert!(nftables.contains("ip6 saddr 2001:db8::1"));
+        assert!(nftables.contains("ip saddr 203.0.113.5"));
+    }
 }
\ No newline at end of file